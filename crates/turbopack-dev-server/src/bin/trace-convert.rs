@@ -0,0 +1,324 @@
+//! Converts a raw `.turbopack/trace.log` (written by the `TURBOPACK_TRACING`
+//! subscriber, see `turbopack_dev_server::trace`) into a Chrome/Perfetto
+//! trace-event JSON file.
+//!
+//! Two views are produced side by side in the same trace, on separate
+//! process ids, so they can be toggled in the Perfetto UI:
+//!   - "single cpu": every span goes on one track, in wall-clock order, so
+//!     time the server spent waiting (no span active) shows up as a gap
+//!     between bars instead of being hidden inside overlapping spans.
+//!   - "merged": every span stays on one track at its real start time, but
+//!     its rendered duration is scaled down by the average number of spans
+//!     that were concurrently active while it ran. Two spans that fully
+//!     overlap for their whole lifetime each get plotted at half their
+//!     actual duration, so their plotted durations add up to the wall-clock
+//!     time either one took alone, instead of implying twice the real
+//!     elapsed time.
+
+use std::{
+    collections::HashMap,
+    env,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    process::ExitCode,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordKind {
+    SpanStart,
+    SpanEnd,
+    Event,
+}
+
+#[derive(Debug, Clone)]
+struct RawRecord {
+    timestamp_ns: u64,
+    span_id: u64,
+    parent_id: u64,
+    kind: RecordKind,
+    name: String,
+}
+
+fn read_records(path: &str) -> io::Result<Vec<RawRecord>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    loop {
+        let mut header = [0u8; 8 + 8 + 8 + 1 + 4];
+        if let Err(err) = reader.read_exact(&mut header) {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                break;
+            }
+            return Err(err);
+        }
+        let timestamp_ns = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let span_id = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let parent_id = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        let kind = match header[24] {
+            0 => RecordKind::SpanStart,
+            1 => RecordKind::SpanEnd,
+            _ => RecordKind::Event,
+        };
+        let name_len = u32::from_le_bytes(header[25..29].try_into().unwrap()) as usize;
+        let mut name = vec![0u8; name_len];
+        reader.read_exact(&mut name)?;
+        records.push(RawRecord {
+            timestamp_ns,
+            span_id,
+            parent_id,
+            kind,
+            name: String::from_utf8_lossy(&name).into_owned(),
+        });
+    }
+    Ok(records)
+}
+
+struct Span {
+    name: String,
+    start_ns: u64,
+    end_ns: u64,
+}
+
+fn reconstruct_spans(records: &[RawRecord]) -> Vec<Span> {
+    let mut open: HashMap<u64, (String, u64)> = HashMap::new();
+    let mut spans = Vec::new();
+    for record in records {
+        match record.kind {
+            RecordKind::SpanStart => {
+                open.insert(record.span_id, (record.name.clone(), record.timestamp_ns));
+            }
+            RecordKind::SpanEnd => {
+                if let Some((name, start_ns)) = open.remove(&record.span_id) {
+                    spans.push(Span {
+                        name,
+                        start_ns,
+                        end_ns: record.timestamp_ns,
+                    });
+                }
+            }
+            RecordKind::Event => {}
+        }
+    }
+    spans.sort_by_key(|s| s.start_ns);
+    spans
+}
+
+fn trace_event(name: &str, pid: u32, tid: u32, start_ns: u64, dur_ns: u64) -> String {
+    format!(
+        "{{\"name\":{name:?},\"ph\":\"X\",\"pid\":{pid},\"tid\":{tid},\"ts\":{ts},\"dur\":{dur}}}",
+        name = name,
+        pid = pid,
+        tid = tid,
+        ts = start_ns / 1000,
+        dur = (dur_ns.max(1)) / 1000,
+    )
+}
+
+/// For each span, the time-weighted average number of spans (itself
+/// included) that were concurrently active while it ran, computed with a
+/// sweep over every span's start/end boundary. A span that never overlaps
+/// anything gets exactly `1.0`; one that overlapped one other span for its
+/// entire lifetime gets `2.0`.
+fn average_concurrency(spans: &[Span]) -> Vec<f64> {
+    let mut boundaries: Vec<u64> = Vec::with_capacity(spans.len() * 2);
+    for span in spans {
+        boundaries.push(span.start_ns);
+        boundaries.push(span.end_ns);
+    }
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    // Number of spans active during the half-open interval
+    // [boundaries[i], boundaries[i + 1]).
+    let levels: Vec<u64> = boundaries
+        .windows(2)
+        .map(|w| {
+            spans
+                .iter()
+                .filter(|s| s.start_ns <= w[0] && s.end_ns >= w[1])
+                .count() as u64
+        })
+        .collect();
+
+    spans
+        .iter()
+        .map(|span| {
+            let total_ns = span.end_ns.saturating_sub(span.start_ns);
+            if total_ns == 0 {
+                return 1.0;
+            }
+            let mut weighted_ns = 0u64;
+            for (w, &level) in boundaries.windows(2).zip(levels.iter()) {
+                let overlap_start = w[0].max(span.start_ns);
+                let overlap_end = w[1].min(span.end_ns);
+                if overlap_end > overlap_start {
+                    weighted_ns += (overlap_end - overlap_start) * level;
+                }
+            }
+            (weighted_ns as f64 / total_ns as f64).max(1.0)
+        })
+        .collect()
+}
+
+fn convert(input: &str, output: &str) -> io::Result<()> {
+    let records = read_records(input)?;
+    let spans = reconstruct_spans(&records);
+
+    const PID_SINGLE_CPU: u32 = 1;
+    const PID_MERGED: u32 = 2;
+
+    let mut events = Vec::new();
+    for span in &spans {
+        events.push(trace_event(
+            &span.name,
+            PID_SINGLE_CPU,
+            0,
+            span.start_ns,
+            span.end_ns - span.start_ns,
+        ));
+    }
+    let concurrency = average_concurrency(&spans);
+    for (span, concurrency) in spans.iter().zip(concurrency.iter()) {
+        let dur_ns = span.end_ns - span.start_ns;
+        let scaled_dur_ns = (dur_ns as f64 / concurrency) as u64;
+        events.push(trace_event(
+            &span.name,
+            PID_MERGED,
+            0,
+            span.start_ns,
+            scaled_dur_ns,
+        ));
+    }
+
+    let mut out = File::create(output)?;
+    writeln!(out, "{{\"traceEvents\":[{}]}}", events.join(","))?;
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let [_, input, output] = <[String; 3]>::try_from(args).unwrap_or_else(|args| {
+        eprintln!(
+            "usage: {} <trace.log> <out.json>",
+            args.first().map(String::as_str).unwrap_or("trace-convert")
+        );
+        std::process::exit(2);
+    });
+    match convert(&input, &output) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("failed to convert trace: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write as _,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    use super::*;
+
+    static NEXT_TEST_FILE: AtomicU64 = AtomicU64::new(0);
+
+    fn temp_log_path() -> std::path::PathBuf {
+        let n = NEXT_TEST_FILE.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(format!("trace-convert-test-{}-{}.log", std::process::id(), n))
+    }
+
+    fn write_record(out: &mut impl io::Write, kind: u8, span_id: u64, parent_id: u64, name: &str) {
+        write_record_at(out, 0, kind, span_id, parent_id, name);
+    }
+
+    fn write_record_at(
+        out: &mut impl io::Write,
+        timestamp_ns: u64,
+        kind: u8,
+        span_id: u64,
+        parent_id: u64,
+        name: &str,
+    ) {
+        let name = name.as_bytes();
+        out.write_all(&timestamp_ns.to_le_bytes()).unwrap();
+        out.write_all(&span_id.to_le_bytes()).unwrap();
+        out.write_all(&parent_id.to_le_bytes()).unwrap();
+        out.write_all(&[kind]).unwrap();
+        out.write_all(&(name.len() as u32).to_le_bytes()).unwrap();
+        out.write_all(name).unwrap();
+    }
+
+    fn span(name: &str, start_ns: u64, end_ns: u64) -> Span {
+        Span {
+            name: name.to_string(),
+            start_ns,
+            end_ns,
+        }
+    }
+
+    #[test]
+    fn reads_back_every_record_written() {
+        let mut bytes = Vec::new();
+        write_record(&mut bytes, 0, 1, 0, "outer");
+        write_record(&mut bytes, 0, 2, 1, "inner");
+        write_record(&mut bytes, 2, 2, 0, "an event");
+        write_record(&mut bytes, 1, 2, 0, "inner");
+        write_record(&mut bytes, 1, 1, 0, "outer");
+
+        let path = temp_log_path();
+        File::create(&path).unwrap().write_all(&bytes).unwrap();
+        let records = read_records(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0].name, "outer");
+        assert_eq!(records[0].kind, RecordKind::SpanStart);
+        assert_eq!(records[2].kind, RecordKind::Event);
+    }
+
+    #[test]
+    fn reconstructs_nested_spans_by_start_time() {
+        let mut bytes = Vec::new();
+        write_record_at(&mut bytes, 1_000, 0, 1, 0, "outer");
+        write_record_at(&mut bytes, 2_000, 0, 2, 1, "inner");
+        write_record_at(&mut bytes, 3_000, 1, 2, 0, "inner");
+        write_record_at(&mut bytes, 4_000, 1, 1, 0, "outer");
+
+        let path = temp_log_path();
+        File::create(&path).unwrap().write_all(&bytes).unwrap();
+        let records = read_records(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        // "inner" closes first, but "outer" started first.
+        let spans = reconstruct_spans(&records);
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].name, "outer");
+        assert_eq!(spans[1].name, "inner");
+    }
+
+    #[test]
+    fn non_overlapping_spans_keep_concurrency_one() {
+        let spans = vec![span("a", 0, 10), span("b", 10, 20)];
+        let concurrency = average_concurrency(&spans);
+        assert_eq!(concurrency, vec![1.0, 1.0]);
+    }
+
+    #[test]
+    fn fully_overlapping_spans_get_concurrency_two() {
+        let spans = vec![span("a", 0, 10), span("b", 0, 10)];
+        let concurrency = average_concurrency(&spans);
+        assert_eq!(concurrency, vec![2.0, 2.0]);
+    }
+
+    #[test]
+    fn partial_overlap_scales_only_the_shared_portion() {
+        // "a" runs [0, 10), "b" runs [5, 10): they share [5, 10) at
+        // concurrency 2, and "a" alone has [0, 5) at concurrency 1, so "a"'s
+        // time-weighted average concurrency is (5 * 1 + 5 * 2) / 10 = 1.5.
+        let spans = vec![span("a", 0, 10), span("b", 5, 10)];
+        let concurrency = average_concurrency(&spans);
+        assert_eq!(concurrency[0], 1.5);
+        assert_eq!(concurrency[1], 2.0);
+    }
+}
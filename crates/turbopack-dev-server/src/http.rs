@@ -0,0 +1,100 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::Stream;
+use turbo_tasks::ReadRef;
+use turbo_tasks_fs::FileContent;
+
+/// Bytes are handed to the client this many at a time, so hyper never needs
+/// more than one chunk resident in the write path per poll. This bounds
+/// *per-poll* allocation, not total response memory — see [`AssetBody`].
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A `Stream` of `Bytes` chunks carved directly out of a `FileContent`, for
+/// use with `hyper::Body::wrap_stream`. `content` is already the asset's
+/// full resolved content — turbo-tasks reads the whole file before handing
+/// back a `FileContent` — so this does *not* bound total response memory to
+/// `CHUNK_SIZE` for large assets; doing that would need a chunked read API
+/// from `turbo_tasks_fs`, which nothing here provides. What this does get
+/// right: instead of one `.to_vec()` copy of the whole content up front,
+/// each chunk is copied out only when it is polled, so at most one chunk's
+/// copy exists at a time, and it gives the response body real backpressure and
+/// cancellation, since `poll_next` only produces the next chunk once hyper
+/// is ready for it — dropping the response body (e.g. on client disconnect)
+/// simply stops polling and the remaining chunks are never produced.
+pub struct AssetBody {
+    content: ReadRef<FileContent>,
+    offset: usize,
+}
+
+impl AssetBody {
+    pub fn new(content: ReadRef<FileContent>) -> Self {
+        Self { content, offset: 0 }
+    }
+}
+
+impl Stream for AssetBody {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let bytes = match &*this.content {
+            FileContent::Content(file) => file.content(),
+            FileContent::NotFound => &[],
+        };
+        match next_chunk(bytes, this.offset) {
+            Some((chunk, offset)) => {
+                this.offset = offset;
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+/// The next up-to-`CHUNK_SIZE` slice of `bytes` starting at `offset`, and
+/// the offset to resume from, or `None` once `offset` has reached the end.
+/// Pulled out of `poll_next` so the chunk-boundary math is testable without
+/// a live `ReadRef<FileContent>`.
+fn next_chunk(bytes: &[u8], offset: usize) -> Option<(Bytes, usize)> {
+    if offset >= bytes.len() {
+        return None;
+    }
+    let len = (bytes.len() - offset).min(CHUNK_SIZE);
+    Some((Bytes::copy_from_slice(&bytes[offset..offset + len]), offset + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_into_chunk_size_pieces() {
+        let bytes = vec![0u8; CHUNK_SIZE + 10];
+        let (chunk, offset) = next_chunk(&bytes, 0).unwrap();
+        assert_eq!(chunk.len(), CHUNK_SIZE);
+        assert_eq!(offset, CHUNK_SIZE);
+
+        let (chunk, offset) = next_chunk(&bytes, offset).unwrap();
+        assert_eq!(chunk.len(), 10);
+        assert_eq!(offset, bytes.len());
+
+        assert!(next_chunk(&bytes, offset).is_none());
+    }
+
+    #[test]
+    fn empty_content_yields_no_chunks() {
+        assert!(next_chunk(&[], 0).is_none());
+    }
+
+    #[test]
+    fn offset_past_end_yields_no_chunks() {
+        let bytes = vec![1, 2, 3];
+        assert!(next_chunk(&bytes, bytes.len()).is_none());
+        assert!(next_chunk(&bytes, bytes.len() + 100).is_none());
+    }
+}
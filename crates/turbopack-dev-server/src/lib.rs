@@ -2,9 +2,13 @@
 
 pub mod fs;
 pub mod html;
+pub mod http;
+pub mod router;
+pub mod trace;
+pub mod update;
 
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     future::Future,
     net::SocketAddr,
     pin::Pin,
@@ -13,29 +17,87 @@ use std::{
 };
 
 use anyhow::{anyhow, Result};
+use futures::{SinkExt, StreamExt};
 use hyper::{
     service::{make_service_fn, service_fn},
-    Body, Request, Response, Server,
+    Body, Method, Request, Response, Server,
 };
+use hyper_tungstenite::{tungstenite::Message, HyperWebsocket};
 use turbo_tasks::{trace::TraceRawVcs, TransientValue};
 use turbo_tasks_fs::{FileContent, FileSystemPathVc};
 use turbopack_core::{
     asset::AssetVc,
-    reference::{all_assets, all_referenced_assets},
+    reference::all_referenced_assets,
 };
 
+use self::{
+    http::AssetBody,
+    router::Router,
+    update::{Update, VersionedContentMapVc, VersionedContentVc},
+};
+
+const HMR_ENDPOINT: &str = "/turbopack-hmr";
+
 #[turbo_tasks::value(shared)]
 enum FindAssetResult {
     NotFound,
     Found(AssetVc),
 }
 
+/// Server sub-path to asset, for every asset reachable from a `root_asset`.
+/// Built once by BFS (see [`DevServerVc::asset_map`]) and memoized by
+/// turbo-tasks, so it only gets rebuilt when the asset graph actually
+/// changes rather than on every request.
+#[turbo_tasks::value(transparent)]
+struct AssetMap(HashMap<String, AssetVc>);
+
+/// How many additional ports to try, in order, if the requested one is
+/// already taken.
+const PORT_FALLBACK_ATTEMPTS: u16 = 10;
+
+/// How often [`DevServerVc::reconcile_content_map`] re-checks `content_map`
+/// against `asset_map` for paths that no longer exist.
+const RECONCILE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The entries of `tracked` for which `still_exists` returns `false`, i.e.
+/// paths `content_map` still has an entry for but that no longer show up in
+/// a freshly rebuilt `asset_map`. Pulled out of
+/// [`DevServerVc::reconcile_once`] so the diffing rule is testable without
+/// a live turbo-tasks runtime.
+fn stale_paths<'a>(
+    tracked: impl IntoIterator<Item = &'a String>,
+    still_exists: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    tracked
+        .into_iter()
+        .filter(|path| !still_exists(path))
+        .cloned()
+        .collect()
+}
+
+/// Quotes `version` as an HTTP `ETag` value.
+fn etag_for(version: &str) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Whether `if_none_match` (the request's `If-None-Match` header, if any)
+/// is exactly `etag`, meaning the client's cached copy is still good and
+/// the request can be answered with `304 Not Modified` instead of resending
+/// the body. A header listing several tags, a weak `W/` tag or `*` is not
+/// recognized and counts as not fresh.
+fn is_fresh(if_none_match: Option<&str>, etag: &str) -> bool {
+    if_none_match == Some(etag)
+}
+
 #[turbo_tasks::value(cell: new, serialization: none, eq: manual)]
 pub struct DevServer {
     root_path: FileSystemPathVc,
     root_asset: AssetVc,
     #[trace_ignore]
     fallback_handler: Arc<dyn Fn(&str) -> Option<String> + Send + Sync>,
+    content_map: VersionedContentMapVc,
+    host: String,
+    port: u16,
 }
 
 #[turbo_tasks::value_impl]
@@ -45,137 +107,385 @@ impl DevServerVc {
         root_path: FileSystemPathVc,
         root_asset: AssetVc,
         fallback_handler: TransientValue<Arc<dyn Fn(&str) -> Option<String> + Send + Sync>>,
+    ) -> Self {
+        Self::with_addr(
+            root_path,
+            root_asset,
+            fallback_handler,
+            "127.0.0.1".to_string(),
+            3000,
+        )
+    }
+
+    /// Like [`Self::new`], but binds to `host:port` (with automatic
+    /// fallback to the next `PORT_FALLBACK_ATTEMPTS` ports if `port` is
+    /// already in use) instead of the hardcoded `127.0.0.1:3000`.
+    #[turbo_tasks::function]
+    pub fn with_addr(
+        root_path: FileSystemPathVc,
+        root_asset: AssetVc,
+        fallback_handler: TransientValue<Arc<dyn Fn(&str) -> Option<String> + Send + Sync>>,
+        host: String,
+        port: u16,
     ) -> Self {
         Self::cell(DevServer {
             root_path,
             root_asset,
             fallback_handler: fallback_handler.into_value(),
+            content_map: VersionedContentMapVc::new(),
+            host,
+            port,
         })
     }
 }
 
 #[turbo_tasks::value_impl]
 impl DevServerVc {
+    /// Builds the full server-path-to-asset index by BFS over
+    /// `all_referenced_assets`, starting from `root_asset`, so it only
+    /// visits assets actually reachable from the entrypoint (same
+    /// reachability rule the old per-request BFS used). This is a regular
+    /// memoized turbo-tasks function: turbo-tasks reruns it only when
+    /// something in the traversed graph invalidates, not on every request.
+    ///
+    /// If two distinct assets collide on the same sub-path, the last one
+    /// visited wins (`HashMap::insert` overwrites), whereas the old
+    /// per-request `find_asset`/`find_asset_2` scan kept whichever it found
+    /// *first* in BFS order. Collisions should be rare in practice; if this
+    /// ever matters, change the `HashMap::insert` below to a
+    /// `HashMap::entry(...).or_insert(...)` to restore first-wins.
     #[turbo_tasks::function]
-    async fn find_asset(self, root_asset: AssetVc, path: &str) -> Result<FindAssetResultVc> {
-        let assets = all_assets(root_asset);
-        let root_path = &*self.await?.root_path.await?;
-        for (p, asset) in assets
-            .await?
-            .iter()
-            .map(|asset| (asset.path(), *asset))
-            .collect::<Vec<_>>()
-        {
-            if let Some(sub_path) = root_path.get_path_to(&*p.await?) {
-                if sub_path == path {
-                    return Ok(FindAssetResult::Found(asset).into());
-                }
-            }
-        }
-        Ok(FindAssetResult::NotFound.into())
-    }
-    #[turbo_tasks::function]
-    async fn find_asset_2(self, root_asset: AssetVc, path: &str) -> Result<FindAssetResultVc> {
+    #[tracing::instrument(skip_all)]
+    async fn asset_map(self, root_asset: AssetVc) -> Result<AssetMapVc> {
         let root_path = &*self.await?.root_path.await?;
-        let p = &*root_asset.path().await?;
+        let mut map = HashMap::new();
         let mut visited = HashSet::new();
         visited.insert(root_asset);
+        if let Some(sub_path) = root_path.get_path_to(&*root_asset.path().await?) {
+            map.insert(sub_path.to_string(), root_asset);
+        }
         let mut queue = VecDeque::new();
-        if let Some(sub_path) = root_path.get_path_to(p) {
-            if sub_path == path {
-                return Ok(FindAssetResult::Found(root_asset).into());
-            }
-            queue.push_back(root_asset);
-            while let Some(asset) = queue.pop_front() {
-                let references = all_referenced_assets(asset).await?;
-                for inner in references.iter() {
-                    if visited.insert(*inner) {
-                        let p = &*inner.path().await?;
-                        if let Some(sub_path) = root_path.get_path_to(p) {
-                            if sub_path == path {
-                                return Ok(FindAssetResult::Found(*inner).into());
-                            }
-                            queue.push_back(*inner);
-                        }
+        queue.push_back(root_asset);
+        while let Some(asset) = queue.pop_front() {
+            let references = all_referenced_assets(asset).await?;
+            for inner in references.iter() {
+                if visited.insert(*inner) {
+                    if let Some(sub_path) = root_path.get_path_to(&*inner.path().await?) {
+                        map.insert(sub_path.to_string(), *inner);
                     }
+                    queue.push_back(*inner);
                 }
             }
         }
-        Ok(FindAssetResult::NotFound.into())
+        Ok(AssetMapVc::cell(map))
+    }
+
+    /// O(1) (after the first build) lookup into the memoized
+    /// [`Self::asset_map`], replacing the old per-request linear/BFS scans.
+    #[turbo_tasks::function]
+    #[tracing::instrument(skip_all, fields(path))]
+    async fn find_asset(self, root_asset: AssetVc, path: &str) -> Result<FindAssetResultVc> {
+        let map = self.asset_map(root_asset).await?;
+        Ok(match map.get(path) {
+            Some(asset) => FindAssetResult::Found(*asset).into(),
+            None => FindAssetResult::NotFound.into(),
+        })
     }
 }
 
 impl DevServerVc {
+    /// Serves a single HMR client: subscribes to `path` on `content_map` and
+    /// forwards every update as a WebSocket message until the client goes
+    /// away.
+    async fn handle_hmr_socket(
+        websocket: HyperWebsocket,
+        content_map: VersionedContentMapVc,
+    ) -> Result<()> {
+        let websocket = websocket.await?;
+        let (mut sink, mut stream) = websocket.split();
+        while let Some(message) = stream.next().await {
+            let path = match message? {
+                Message::Text(path) => path,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let mut updates = Box::pin(content_map.subscribe(path).await?);
+            while let Some(update) = updates.next().await {
+                let text = match update {
+                    Update::Partial {
+                        path,
+                        from,
+                        to,
+                        content,
+                    } => format!(
+                        "{{\"type\":\"partial\",\"path\":{:?},\"from\":{:?},\"to\":{:?},\"content\":{:?}}}",
+                        path,
+                        from,
+                        to,
+                        String::from_utf8_lossy(&content)
+                    ),
+                    Update::Evicted { path } => {
+                        format!("{{\"type\":\"evicted\",\"path\":{:?}}}", path)
+                    }
+                };
+                if sink.send(Message::text(text)).await.is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `find_asset`/`content_map`/`fallback_handler` resolution for a
+    /// single request inside a `turbo_tasks::run_once` task, same as the
+    /// lookup `listen` always did, now reachable as an ordinary route
+    /// handler instead of being inlined into the connection loop.
+    #[tracing::instrument(skip_all, fields(path = %request.uri().path()))]
+    async fn handle_asset_request(
+        self,
+        root_asset: AssetVc,
+        content_map: VersionedContentMapVc,
+        fallback_handler: Arc<dyn Fn(&str) -> Option<String> + Send + Sync>,
+        tt: Arc<dyn turbo_tasks::TurboTasksApi>,
+        request: Request<Body>,
+        params: router::Params,
+    ) -> Result<Response<Body>> {
+        let start = Instant::now();
+        let path = request.uri().path().to_string();
+        let mut asset_path = params.get("path").unwrap_or_default().to_string();
+        if asset_path.is_empty() || asset_path.ends_with('/') {
+            asset_path += "index.html";
+        }
+        let if_none_match = request
+            .headers()
+            .get(hyper::header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let task_id = tt.run_once(Box::pin(async move {
+            if let FindAssetResult::Found(asset) = &*self
+                .find_asset(root_asset, &asset_path)
+                .strongly_consistent()
+                .await?
+            {
+                content_map.insert(asset_path.clone(), *asset).await?;
+                let version = VersionedContentVc::new(*asset)
+                    .version()
+                    .strongly_consistent()
+                    .await?;
+                let etag = etag_for(&version);
+                if is_fresh(if_none_match.as_deref(), &etag) {
+                    tx.send(
+                        Response::builder()
+                            .status(304)
+                            .header(hyper::header::ETAG, etag)
+                            .body(Body::empty())?,
+                    )
+                    .map_err(|_| anyhow!("receiver dropped"))?;
+                    println!("[304] {} ({}ms)", path, start.elapsed().as_millis());
+                    return Ok(());
+                }
+                let content = tracing::info_span!("read_content")
+                    .in_scope(|| asset.content().strongly_consistent())
+                    .await?;
+                if matches!(&*content, FileContent::Content(_)) {
+                    let content_type = mime_guess::from_path(&asset_path)
+                        .first_or_octet_stream()
+                        .to_string();
+                    tx.send(
+                        Response::builder()
+                            .status(200)
+                            .header(hyper::header::CONTENT_TYPE, content_type)
+                            .header(hyper::header::ETAG, etag)
+                            .body(Body::wrap_stream(AssetBody::new(content)))?,
+                    )
+                    .map_err(|_| anyhow!("receiver dropped"))?;
+                    println!("[200] {} ({}ms)", path, start.elapsed().as_millis());
+                    return Ok(());
+                }
+            }
+            let response = Self::fallback_response(&fallback_handler, &path)?;
+            let status = response.status();
+            tx.send(response).map_err(|_| anyhow!("receiver dropped"))?;
+            println!("[{}] {} ({}ms)", status, path, start.elapsed().as_millis());
+            Ok(())
+        }));
+        loop {
+            match unsafe { tt.try_read_task_output_untracked(task_id, false)? } {
+                Ok(_) => break,
+                Err(listener) => listener.await,
+            }
+        }
+        Ok(rx.await?)
+    }
+
+    fn fallback_response(
+        fallback_handler: &Arc<dyn Fn(&str) -> Option<String> + Send + Sync>,
+        path: &str,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        match fallback_handler(path) {
+            Some(content) => Response::builder().status(200).body(Body::from(content)),
+            None => Response::builder().status(404).body(Body::empty()),
+        }
+    }
+
+    /// Background task started once per server: periodically re-checks
+    /// `asset_map` (which turbo-tasks keeps up to date on its own) against
+    /// what `content_map` currently tracks, and evicts any path whose asset
+    /// is no longer reachable from `root_asset` — e.g. a file was deleted —
+    /// so a subscribed HMR client gets `Update::Evicted` even if it never
+    /// issues another GET for that path.
+    async fn reconcile_content_map(self, root_asset: AssetVc, content_map: VersionedContentMapVc) {
+        loop {
+            tokio::time::sleep(RECONCILE_INTERVAL).await;
+            if let Err(err) = self.reconcile_once(root_asset, content_map).await {
+                println!("[hmr] reconcile error: {}", err);
+            }
+        }
+    }
+
+    async fn reconcile_once(
+        self,
+        root_asset: AssetVc,
+        content_map: VersionedContentMapVc,
+    ) -> Result<()> {
+        let tt = turbo_tasks::turbo_tasks();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let task_id = tt.run_once(Box::pin(async move {
+            let tracked = content_map
+                .keys_for_entrypoint(String::new())
+                .strongly_consistent()
+                .await?;
+            let current = self.asset_map(root_asset).strongly_consistent().await?;
+            for path in stale_paths(tracked.iter(), |path| current.contains_key(path)) {
+                content_map.remove(path).await?;
+            }
+            tx.send(()).map_err(|_| anyhow!("receiver dropped"))?;
+            Ok(())
+        }));
+        loop {
+            match unsafe { tt.try_read_task_output_untracked(task_id, false)? } {
+                Ok(_) => break,
+                Err(listener) => listener.await,
+            }
+        }
+        rx.await?;
+        Ok(())
+    }
+
+    /// Binds to `host:port`, retrying on the next `PORT_FALLBACK_ATTEMPTS`
+    /// ports if it's already taken.
+    fn bind(
+        host: &str,
+        port: u16,
+    ) -> Result<(
+        hyper::server::Builder<hyper::server::conn::AddrIncoming>,
+        SocketAddr,
+    )> {
+        let ip: std::net::IpAddr = host.parse()?;
+        let mut last_err = None;
+        for candidate in port..=port.saturating_add(PORT_FALLBACK_ATTEMPTS) {
+            let addr = SocketAddr::from((ip, candidate));
+            match Server::try_bind(&addr) {
+                Ok(builder) => return Ok((builder, addr)),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.expect("at least one bind attempt").into())
+    }
+
     pub async fn listen(self) -> Result<DevServerListening> {
-        let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
         let tt = turbo_tasks::turbo_tasks();
         let this = self.await?;
+        let trace_root = this
+            .root_path
+            .to_sys_path()
+            .await?
+            .unwrap_or_else(|| std::env::current_dir().expect("cwd should exist"));
+        trace::try_init(&trace_root)?;
         let root_asset = this.root_asset;
         let fallback_handler = this.fallback_handler.clone();
-        let make_svc = make_service_fn(move |_| {
-            let tt = tt.clone();
-            let fallback_handler = fallback_handler.clone();
-            async move {
-                let handler = move |request: Request<Body>| {
-                    let start = Instant::now();
-                    let tt = tt.clone();
-                    let fallback_handler = fallback_handler.clone();
+        let content_map = this.content_map;
+        let host = this.host.clone();
+        let port = this.port;
+
+        tokio::task::spawn(self.reconcile_content_map(root_asset, content_map));
+
+        let mut router = Router::new();
+        router.route(
+            Method::GET,
+            HMR_ENDPOINT,
+            {
+                let content_map = content_map;
+                move |mut request: Request<Body>, _params: router::Params| {
+                    let content_map = content_map;
                     async move {
-                        let (tx, rx) = tokio::sync::oneshot::channel();
-                        let task_id = tt.run_once(Box::pin(async move {
-                            let uri = request.uri();
-                            let path = uri.path();
-                            let mut asset_path = path[1..].to_string();
-                            if asset_path == "" || asset_path.ends_with("/") {
-                                asset_path += "index.html";
-                            }
-                            if let FindAssetResult::Found(asset) = &*self
-                                .find_asset(root_asset, &asset_path)
-                                .strongly_consistent()
-                                .await?
+                        if !hyper_tungstenite::is_upgrade_request(&request) {
+                            return Ok(Response::builder().status(400).body(Body::empty())?);
+                        }
+                        let (response, websocket) =
+                            hyper_tungstenite::upgrade(&mut request, None)?;
+                        tokio::task::spawn(async move {
+                            if let Err(err) = Self::handle_hmr_socket(websocket, content_map).await
                             {
-                                if let FileContent::Content(content) =
-                                    &*asset.content().strongly_consistent().await?
-                                {
-                                    tx.send(
-                                        Response::builder()
-                                            .status(200)
-                                            .body(Body::from(content.content().to_vec()))?,
-                                    )
-                                    .map_err(|_| anyhow!("receiver dropped"))?;
-                                    println!("[200] {} ({}ms)", path, start.elapsed().as_millis());
-                                    return Ok(());
-                                }
+                                println!("[hmr] client error: {}", err);
                             }
-                            if let Some(content) = fallback_handler(path) {
-                                tx.send(Response::builder().status(200).body(Body::from(content))?)
-                                    .map_err(|_| anyhow!("receiver dropped"))?;
-                                println!("[200] {} ({}ms)", path, start.elapsed().as_millis());
-                                return Ok(());
-                            }
-                            tx.send(Response::builder().status(404).body(Body::empty())?)
-                                .map_err(|_| anyhow!("receiver dropped"))?;
-                            println!("[404] {} ({}ms)", path, start.elapsed().as_millis());
-                            Ok(())
-                        }));
-                        loop {
-                            match unsafe { tt.try_read_task_output_untracked(task_id, false)? } {
-                                Ok(_) => break,
-                                Err(listener) => listener.await,
-                            }
-                        }
-                        Ok::<_, anyhow::Error>(rx.await?)
+                        });
+                        Ok(response)
                     }
-                };
-                Ok::<_, anyhow::Error>(service_fn(handler))
+                }
+            },
+        );
+        router.route(Method::GET, "*", {
+            let tt = tt.clone();
+            let fallback_handler = fallback_handler.clone();
+            move |request: Request<Body>, params: router::Params| {
+                let tt = tt.clone();
+                let fallback_handler = fallback_handler.clone();
+                self.handle_asset_request(
+                    root_asset,
+                    content_map,
+                    fallback_handler,
+                    tt,
+                    request,
+                    params,
+                )
+            }
+        });
+        // No `router.fallback(...)` here: the `Method::GET, "*"` route above
+        // already matches every path (it's a wildcard), so no request can
+        // ever reach a router-level fallback — `handle_asset_request`
+        // already calls `Self::fallback_response` inline when `find_asset`
+        // comes back empty, which is the only "nothing matched" case that
+        // can actually occur with this routing table.
+        let router = Arc::new(router);
+
+        let make_svc = make_service_fn(move |_| {
+            let router = router.clone();
+            async move {
+                let router = router.clone();
+                Ok::<_, anyhow::Error>(service_fn(move |request| {
+                    let router = router.clone();
+                    async move { router.handle(request).await }
+                }))
             }
         });
-        let server = Server::bind(&addr).serve(make_svc);
+        let (builder, addr) = Self::bind(&host, port)?;
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let server = builder
+            .serve(make_svc)
+            .with_graceful_shutdown(async move {
+                let _ = shutdown_rx.await;
+            });
         println!("server listening on: {}", addr);
-        Ok(DevServerListening::new(async move {
-            server.await?;
-            Ok(())
-        }))
+        Ok(DevServerListening::new(
+            async move {
+                server.await?;
+                Ok(())
+            },
+            shutdown_tx,
+        ))
     }
 }
 
@@ -183,12 +493,29 @@ impl DevServerVc {
 pub struct DevServerListening {
     #[trace_ignore]
     pub future: Pin<Box<dyn Future<Output = Result<()>> + Send + 'static>>,
+    /// Triggers graceful shutdown: in-flight requests are drained before
+    /// `future` resolves, rather than the server running until the process
+    /// is killed.
+    #[trace_ignore]
+    shutdown: Option<tokio::sync::oneshot::Sender<()>>,
 }
 
 impl DevServerListening {
-    fn new(future: impl Future<Output = Result<()>> + Send + 'static) -> Self {
+    fn new(
+        future: impl Future<Output = Result<()>> + Send + 'static,
+        shutdown: tokio::sync::oneshot::Sender<()>,
+    ) -> Self {
         Self {
             future: Box::pin(future),
+            shutdown: Some(shutdown),
+        }
+    }
+
+    /// Stops the server, draining in-flight requests instead of dropping
+    /// them. After calling this, await `future` to know when it's done.
+    pub fn stop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
         }
     }
 }
@@ -199,3 +526,40 @@ pub fn register() {
     turbopack_core::register();
     include!(concat!(env!("OUT_DIR"), "/register.rs"));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_paths_are_the_ones_missing_from_current() {
+        let tracked = vec!["a.js".to_string(), "b.js".to_string(), "c.js".to_string()];
+        let current: HashSet<&str> = ["a.js", "c.js"].into_iter().collect();
+        let stale = stale_paths(tracked.iter(), |path| current.contains(path));
+        assert_eq!(stale, vec!["b.js".to_string()]);
+    }
+
+    #[test]
+    fn stale_paths_is_empty_when_everything_still_exists() {
+        let tracked = vec!["a.js".to_string()];
+        assert!(stale_paths(tracked.iter(), |_| true).is_empty());
+    }
+
+    #[test]
+    fn etag_is_the_quoted_version() {
+        assert_eq!(etag_for("abc123"), "\"abc123\"");
+    }
+
+    #[test]
+    fn matching_if_none_match_is_fresh() {
+        let etag = etag_for("abc123");
+        assert!(is_fresh(Some(etag.as_str()), &etag));
+    }
+
+    #[test]
+    fn mismatched_or_missing_if_none_match_is_not_fresh() {
+        let etag = etag_for("abc123");
+        assert!(!is_fresh(Some("\"stale\""), &etag));
+        assert!(!is_fresh(None, &etag));
+    }
+}
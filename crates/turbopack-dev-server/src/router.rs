@@ -0,0 +1,261 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use anyhow::Result;
+use hyper::{Body, Method, Request, Response};
+
+pub type RouteFuture = Pin<Box<dyn Future<Output = Result<Response<Body>>> + Send>>;
+
+/// Parameters captured from a route pattern's `:name` (or `*`) segments, in
+/// declaration order.
+#[derive(Debug, Default, Clone)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub trait RouteHandler: Send + Sync {
+    fn handle(&self, request: Request<Body>, params: Params) -> RouteFuture;
+}
+
+impl<F, Fut> RouteHandler for F
+where
+    F: Fn(Request<Body>, Params) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Response<Body>>> + Send + 'static,
+{
+    fn handle(&self, request: Request<Body>, params: Params) -> RouteFuture {
+        Box::pin(self(request, params))
+    }
+}
+
+enum Pattern {
+    /// Matches any path, capturing the whole thing (without leading slash)
+    /// as the `path` param. Used for the asset handler, which needs to
+    /// accept arbitrarily nested paths.
+    Wildcard,
+    Segments(Vec<Segment>),
+}
+
+enum Segment {
+    Exact(String),
+    Param(String),
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        if pattern == "*" {
+            return Pattern::Wildcard;
+        }
+        let segments = pattern
+            .trim_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| match s.strip_prefix(':') {
+                Some(name) => Segment::Param(name.to_string()),
+                None => Segment::Exact(s.to_string()),
+            })
+            .collect();
+        Pattern::Segments(segments)
+    }
+
+    fn matches(&self, path: &str) -> Option<Params> {
+        match self {
+            Pattern::Wildcard => Some(Params(vec![(
+                "path".to_string(),
+                path.trim_start_matches('/').to_string(),
+            )])),
+            Pattern::Segments(segments) => {
+                let parts: Vec<&str> = path
+                    .trim_matches('/')
+                    .split('/')
+                    .filter(|s| !s.is_empty())
+                    .collect();
+                if parts.len() != segments.len() {
+                    return None;
+                }
+                let mut params = Vec::new();
+                for (segment, part) in segments.iter().zip(parts.iter()) {
+                    match segment {
+                        Segment::Exact(expected) => {
+                            if expected != part {
+                                return None;
+                            }
+                        }
+                        Segment::Param(name) => params.push((name.clone(), (*part).to_string())),
+                    }
+                }
+                Some(Params(params))
+            }
+        }
+    }
+
+    /// Patterns without any capturing segment are tried first, so a literal
+    /// route always wins over a broader pattern registered for the same
+    /// path (e.g. `/turbopack-hmr` over the asset handler's `*`).
+    fn is_exact(&self) -> bool {
+        matches!(self, Pattern::Segments(segments) if segments.iter().all(|s| matches!(s, Segment::Exact(_))))
+    }
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Arc<dyn RouteHandler>,
+}
+
+/// Maps `(Method, path pattern)` to handlers. A request is resolved by
+/// checking, in order: exact-path routes (registration order), then
+/// parameterized/wildcard routes (registration order), then the fallback
+/// route. A path that matches some route's pattern but not under the
+/// request's method is rejected with `405` rather than falling through.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<Arc<dyn RouteHandler>>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        handler: impl RouteHandler + 'static,
+    ) -> &mut Self {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::parse(pattern),
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Registers the terminal handler reached when no route's pattern
+    /// matches the request path at all. The handler sees the full request,
+    /// not just the path.
+    pub fn fallback(&mut self, handler: impl RouteHandler + 'static) -> &mut Self {
+        self.fallback = Some(Arc::new(handler));
+        self
+    }
+
+    pub async fn handle(&self, request: Request<Body>) -> Result<Response<Body>> {
+        let path = request.uri().path().to_string();
+        let method = request.method().clone();
+
+        for exact_pass in [true, false] {
+            let mut path_matched_this_pass = false;
+            for route in &self.routes {
+                if route.pattern.is_exact() != exact_pass {
+                    continue;
+                }
+                if let Some(params) = route.pattern.matches(&path) {
+                    path_matched_this_pass = true;
+                    if route.method == method {
+                        return route.handler.handle(request, params).await;
+                    }
+                }
+            }
+            // A route's pattern already matched the path in this pass, just not
+            // under this method: resolve the request right here rather than
+            // letting a later, broader pass (e.g. the wildcard asset handler)
+            // dispatch it under a method the more specific route never allowed.
+            if path_matched_this_pass {
+                return Ok(Response::builder().status(405).body(Body::empty())?);
+            }
+        }
+
+        if let Some(fallback) = &self.fallback {
+            return fallback.handle(request, Params::default()).await;
+        }
+
+        Ok(Response::builder().status(404).body(Body::empty())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn ok(body: &str) -> Result<Response<Body>> {
+        Ok(Response::builder().status(200).body(Body::from(body.to_string()))?)
+    }
+
+    fn request(method: Method, path: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn status(router: &Router, method: Method, path: &str) -> u16 {
+        router.handle(request(method, path)).await.unwrap().status().as_u16()
+    }
+
+    async fn body_text(response: Response<Body>) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn exact_route_wins_over_wildcard() {
+        let mut router = Router::new();
+        router.route(Method::GET, "/turbopack-hmr", |_, _| async { ok("hmr").await });
+        router.route(Method::GET, "*", |_, _| async { ok("asset").await });
+
+        let response = router
+            .handle(request(Method::GET, "/turbopack-hmr"))
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(body_text(response).await, "hmr");
+    }
+
+    #[tokio::test]
+    async fn wildcard_captures_full_path_as_param() {
+        let mut router = Router::new();
+        router.route(Method::GET, "*", |_, params: Params| async move {
+            ok(params.get("path").unwrap_or("")).await
+        });
+
+        let response = router
+            .handle(request(Method::GET, "/a/b/c"))
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(body_text(response).await, "a/b/c");
+    }
+
+    #[tokio::test]
+    async fn method_mismatch_on_exact_route_is_405_not_fallthrough() {
+        let mut router = Router::new();
+        router.route(Method::GET, "/turbopack-hmr", |_, _| async { ok("hmr").await });
+        router.route(Method::GET, "*", |_, _| async { ok("asset").await });
+
+        // PUT doesn't match the exact route's method, and must not silently
+        // fall through to the wildcard GET route just because that route
+        // happens to accept some method for this path.
+        assert_eq!(status(&router, Method::PUT, "/turbopack-hmr").await, 405);
+    }
+
+    #[tokio::test]
+    async fn unmatched_path_with_no_fallback_is_404() {
+        let router = Router::new();
+        assert_eq!(status(&router, Method::GET, "/nope").await, 404);
+    }
+
+    #[tokio::test]
+    async fn unmatched_path_uses_fallback() {
+        let mut router = Router::new();
+        router.fallback(|_, _| async { ok("fallback").await });
+        assert_eq!(status(&router, Method::GET, "/nope").await, 200);
+    }
+}
@@ -0,0 +1,113 @@
+//! Opt-in profiling: set `TURBOPACK_TRACING=1` to have `listen` and the
+//! asset-lookup functions emit spans into `.turbopack/trace.log` under the
+//! dev server's root path. The on-disk format is append-only fixed records
+//! so the hot path only writes a few integers and the name into a buffered
+//! writer (flushed whenever a span closes); `trace-convert`
+//! (the companion binary in `src/bin`) turns the log into a Chrome/Perfetto
+//! trace JSON.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use tracing::span;
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+pub const ENV_VAR: &str = "TURBOPACK_TRACING";
+pub const TRACE_FILE: &str = ".turbopack/trace.log";
+
+#[repr(u8)]
+enum RecordKind {
+    SpanStart = 0,
+    SpanEnd = 1,
+    Event = 2,
+}
+
+/// One fixed-size entry in the raw trace log: `timestamp_ns: u64, span_id:
+/// u64, parent_id: u64, kind: u8, name_len: u32` followed by `name_len`
+/// bytes of the span/event name. Keeping everything but the name
+/// fixed-width means the writer never needs to serialize more than a
+/// handful of integers per call.
+fn write_record(out: &mut impl Write, kind: RecordKind, span_id: u64, parent_id: u64, name: &str) {
+    let timestamp_ns = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let name = name.as_bytes();
+    let _ = out.write_all(&timestamp_ns.to_le_bytes());
+    let _ = out.write_all(&span_id.to_le_bytes());
+    let _ = out.write_all(&parent_id.to_le_bytes());
+    let _ = out.write_all(&[kind as u8]);
+    let _ = out.write_all(&(name.len() as u32).to_le_bytes());
+    let _ = out.write_all(name);
+}
+
+struct RawLogLayer {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl<S> Layer<S> for RawLogLayer
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let parent_id = ctx
+            .span(id)
+            .and_then(|s| s.parent().map(|p| p.id().into_u64()))
+            .unwrap_or(0);
+        let mut out = self.out.lock().unwrap();
+        write_record(
+            &mut *out,
+            RecordKind::SpanStart,
+            id.into_u64(),
+            parent_id,
+            attrs.metadata().name(),
+        );
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let name = ctx
+            .span(&id)
+            .map(|s| s.metadata().name())
+            .unwrap_or("unknown");
+        let mut out = self.out.lock().unwrap();
+        write_record(&mut *out, RecordKind::SpanEnd, id.into_u64(), 0, name);
+        let _ = out.flush();
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        let span_id = ctx.event_span(event).map(|s| s.id().into_u64()).unwrap_or(0);
+        let mut out = self.out.lock().unwrap();
+        write_record(
+            &mut *out,
+            RecordKind::Event,
+            span_id,
+            0,
+            event.metadata().name(),
+        );
+    }
+}
+
+/// Installs the raw-log tracing subscriber if `TURBOPACK_TRACING` is set,
+/// writing to `root_path/.turbopack/trace.log`. A no-op otherwise, so the
+/// hot path pays nothing when profiling isn't requested.
+pub fn try_init(root_path: &Path) -> Result<()> {
+    if std::env::var_os(ENV_VAR).is_none() {
+        return Ok(());
+    }
+    let log_path = root_path.join(TRACE_FILE);
+    if let Some(dir) = log_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(log_path)?;
+    let layer = RawLogLayer {
+        out: Mutex::new(BufWriter::new(file)),
+    };
+    tracing_subscriber::registry().with(layer).init();
+    Ok(())
+}
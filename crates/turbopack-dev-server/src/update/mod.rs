@@ -0,0 +1,283 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use turbo_tasks_fs::FileContent;
+use turbopack_core::asset::AssetVc;
+
+#[turbo_tasks::value(transparent)]
+pub struct OptionAssetVc(Option<AssetVc>);
+
+/// An asset paired with its (lazily computed, memoized) content hash, used
+/// as the version identifier for HMR and conditional requests alike.
+#[turbo_tasks::value(shared)]
+pub struct VersionedContent {
+    pub asset: AssetVc,
+}
+
+#[turbo_tasks::value_impl]
+impl VersionedContentVc {
+    #[turbo_tasks::function]
+    pub fn new(asset: AssetVc) -> Self {
+        Self::cell(VersionedContent { asset })
+    }
+
+    /// A content hash identifying this version. Two calls with unchanged
+    /// content resolve to the same string; turbo-tasks invalidates this as
+    /// soon as the underlying asset content changes.
+    #[turbo_tasks::function]
+    pub async fn version(self) -> Result<turbo_tasks::primitives::StringVc> {
+        let this = self.await?;
+        let content = this.asset.content().await?;
+        let hash = match &*content {
+            FileContent::Content(file) => {
+                let mut hasher = Sha256::new();
+                hasher.update(file.content());
+                format!("{:x}", hasher.finalize())
+            }
+            FileContent::NotFound => "not-found".to_string(),
+        };
+        Ok(turbo_tasks::primitives::StringVc::cell(hash))
+    }
+}
+
+/// An update pushed to a subscribed HMR client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Update {
+    /// The content at `path` changed from `from` to `to`; `content` carries
+    /// the new bytes so the client can apply a partial update without a
+    /// round trip.
+    Partial {
+        path: String,
+        from: String,
+        to: String,
+        content: Vec<u8>,
+    },
+    /// The asset previously served at `path` no longer exists.
+    Evicted { path: String },
+}
+
+/// How often a subscription re-checks `path`'s version. There's no push
+/// notification from turbo-tasks available here, so this polls — each tick
+/// takes the asset that `insert` stored for `path` and re-reads its version
+/// and content through turbo-tasks (see
+/// [`VersionedContentMapVc::poll_once`]), so a content change is noticed
+/// even if no HTTP request ever touches `path` again.
+const SUBSCRIBE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Maps server paths to the asset currently serving them, so HTTP GETs and
+/// HMR subscriptions share this single source of truth instead of each
+/// re-walking the asset graph.
+#[turbo_tasks::value(cell: new, serialization: none, eq: manual)]
+pub struct VersionedContentMap {
+    #[trace_ignore]
+    map: std::sync::Mutex<HashMap<String, AssetVc>>,
+}
+
+#[turbo_tasks::value_impl]
+impl VersionedContentMapVc {
+    #[turbo_tasks::function]
+    pub fn new() -> Self {
+        Self::cell(VersionedContentMap {
+            map: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Inserts or replaces the asset served at `path`.
+    #[turbo_tasks::function]
+    pub async fn insert(self, path: String, asset: AssetVc) -> Result<()> {
+        let this = self.await?;
+        this.map.lock().unwrap().insert(path, asset);
+        Ok(())
+    }
+
+    /// Removes the asset at `path`. Subscribers notice on their next poll
+    /// (see [`Self::subscribe`]) and emit `Update::Evicted`.
+    #[turbo_tasks::function]
+    pub async fn remove(self, path: String) -> Result<()> {
+        let this = self.await?;
+        this.map.lock().unwrap().remove(&path);
+        Ok(())
+    }
+
+    /// Looks up the asset currently served at `path`.
+    #[turbo_tasks::function]
+    pub async fn get(self, path: String) -> Result<OptionAssetVc> {
+        let this = self.await?;
+        let asset = this.map.lock().unwrap().get(&path).copied();
+        Ok(OptionAssetVc::cell(asset))
+    }
+
+    /// Server paths currently registered under `entrypoint`, so deleted
+    /// assets belonging to a rebuilt entrypoint can be evicted. This dev
+    /// server only ever serves a single entrypoint, so `listen`'s
+    /// reconciliation loop passes `""`, which `starts_with` matches against
+    /// every path.
+    #[turbo_tasks::function]
+    pub async fn keys_for_entrypoint(
+        self,
+        entrypoint: String,
+    ) -> Result<turbo_tasks::primitives::StringsVc> {
+        let this = self.await?;
+        let keys = this
+            .map
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|path| path.starts_with(&entrypoint))
+            .cloned()
+            .collect();
+        Ok(turbo_tasks::primitives::StringsVc::cell(keys))
+    }
+}
+
+/// Decides what (if anything) a subscription should yield after polling
+/// `path` and getting back `current` (its version and content, or `None` if
+/// the asset no longer exists), given `last_version` (the version the
+/// subscription last yielded, or `None` if it hasn't yielded yet).
+/// Pulled out of [`VersionedContentMapVc::subscribe`]'s poll loop so the
+/// version-diffing and eviction-detection rules are testable without a
+/// live turbo-tasks runtime.
+fn next_update(
+    path: &str,
+    last_version: Option<&str>,
+    current: Option<(&str, Vec<u8>)>,
+) -> Option<Update> {
+    match current {
+        Some((version, content)) if last_version != Some(version) => Some(Update::Partial {
+            path: path.to_string(),
+            from: last_version.unwrap_or_default().to_string(),
+            to: version.to_string(),
+            content,
+        }),
+        Some(_) => None,
+        None if last_version.is_some() => Some(Update::Evicted {
+            path: path.to_string(),
+        }),
+        None => None,
+    }
+}
+
+impl VersionedContentMapVc {
+    /// Resolves `path`'s current asset and version inside a fresh
+    /// `turbo_tasks::run_once` task, waiting on it with the same
+    /// task-id/listener loop `handle_asset_request` uses to wait on its own
+    /// one-shot task. The asset itself is the one `insert` stored for
+    /// `path`; its version and content are read through turbo-tasks on
+    /// every call, the same way serving an HTTP request would.
+    async fn poll_once(self, path: String) -> Result<Option<(String, Vec<u8>)>> {
+        let tt = turbo_tasks::turbo_tasks();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let task_id = tt.run_once(Box::pin(async move {
+            let asset: Option<AssetVc> = **self.get(path).strongly_consistent().await?;
+            let result = match asset {
+                Some(asset) => {
+                    let version = VersionedContentVc::new(asset)
+                        .version()
+                        .strongly_consistent()
+                        .await?;
+                    let content = match &*asset.content().strongly_consistent().await? {
+                        FileContent::Content(file) => file.content().to_vec(),
+                        FileContent::NotFound => Vec::new(),
+                    };
+                    Some(((*version).clone(), content))
+                }
+                None => None,
+            };
+            tx.send(result).map_err(|_| anyhow!("receiver dropped"))?;
+            Ok(())
+        }));
+        loop {
+            match unsafe { tt.try_read_task_output_untracked(task_id, false)? } {
+                Ok(_) => break,
+                Err(listener) => listener.await,
+            }
+        }
+        Ok(rx.await?)
+    }
+
+    /// Subscribes to content changes at `path`. The returned stream runs
+    /// its own poll loop, driven by whoever polls the stream, that re-reads
+    /// `path`'s asset through turbo-tasks on every tick (see
+    /// [`Self::poll_once`]), so a change on disk reaches the client even if
+    /// nothing ever requests `path` again.
+    pub async fn subscribe(
+        self,
+        path: String,
+    ) -> Result<impl futures::Stream<Item = Update> + Send + 'static> {
+        let map = self;
+        Ok(async_stream::stream! {
+            let mut last_version: Option<String> = None;
+            loop {
+                let current = map.poll_once(path.clone()).await.unwrap_or(None);
+                let update = next_update(
+                    &path,
+                    last_version.as_deref(),
+                    current.as_ref().map(|(v, c)| (v.as_str(), c.clone())),
+                );
+                if let Some(update) = update {
+                    last_version = match &update {
+                        Update::Partial { to, .. } => Some(to.clone()),
+                        Update::Evicted { .. } => None,
+                    };
+                    yield update;
+                }
+                tokio::time::sleep(SUBSCRIBE_POLL_INTERVAL).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_observation_emits_partial_from_empty_string() {
+        let update = next_update("a.js", None, Some(("v1", b"hi".to_vec()))).unwrap();
+        assert_eq!(
+            update,
+            Update::Partial {
+                path: "a.js".to_string(),
+                from: String::new(),
+                to: "v1".to_string(),
+                content: b"hi".to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn unchanged_version_emits_nothing() {
+        assert_eq!(next_update("a.js", Some("v1"), Some(("v1", Vec::new()))), None);
+    }
+
+    #[test]
+    fn changed_version_emits_partial_with_from_and_to() {
+        let update = next_update("a.js", Some("v1"), Some(("v2", b"new".to_vec()))).unwrap();
+        assert_eq!(
+            update,
+            Update::Partial {
+                path: "a.js".to_string(),
+                from: "v1".to_string(),
+                to: "v2".to_string(),
+                content: b"new".to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn disappearing_after_being_seen_emits_evicted() {
+        let update = next_update("a.js", Some("v1"), None).unwrap();
+        assert_eq!(
+            update,
+            Update::Evicted {
+                path: "a.js".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn never_seen_and_still_absent_emits_nothing() {
+        assert_eq!(next_update("a.js", None, None), None);
+    }
+}